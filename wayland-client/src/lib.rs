@@ -0,0 +1,242 @@
+extern crate downcast_rs;
+extern crate libc;
+extern crate wayland_commons;
+
+mod rust_imp;
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+pub use wayland_commons::Interface;
+
+pub use rust_imp::RawEvent;
+
+use rust_imp::{Connection, EventQueueInner, ProxyInner};
+
+/// A handle to a wire object on the client side
+///
+/// Cloning a `Proxy` gives another handle to the very same protocol object; it does not
+/// create a new one.
+#[derive(Clone)]
+pub struct Proxy<I: Interface> {
+    inner: ProxyInner,
+    _i: ::std::marker::PhantomData<*const I>,
+}
+
+impl<I: Interface> Proxy<I> {
+    pub(crate) fn wrap(inner: ProxyInner) -> Proxy<I> {
+        Proxy {
+            inner,
+            _i: ::std::marker::PhantomData,
+        }
+    }
+}
+
+/// A newly created wire object, not yet associated with an implementation
+pub struct NewProxy<I: Interface> {
+    inner: rust_imp::NewProxyInner,
+    _i: ::std::marker::PhantomData<*const I>,
+}
+
+impl<I: Interface> NewProxy<I> {
+    /// Implement this proxy with an `Implementation` handling its typed events
+    ///
+    /// This is the usual way to attach a handler to a single object; use `implement_raw`
+    /// or `assign` instead if the concrete `I::Event` isn't known statically, or the
+    /// handler is shared with other proxies.
+    pub fn implement<Impl>(self, implementation: Impl) -> Proxy<I>
+    where
+        Impl: Implementation<Proxy<I>, I::Event> + 'static,
+        I::Event: wayland_commons::MessageGroup<Map = rust_imp::ProxyMap>,
+    {
+        let inner = unsafe { self.inner.implement::<I, Impl>(implementation) };
+        Proxy::wrap(inner)
+    }
+
+    /// Implement this proxy with a catch-all callback receiving undecoded `RawEvent`s
+    ///
+    /// Use this instead of `implement` when the concrete `I::Event` isn't needed, e.g.
+    /// to build a protocol proxy, logger or debugger for an interface that isn't bound
+    /// statically.
+    pub fn implement_raw<F>(self, callback: F) -> Proxy<I>
+    where
+        F: FnMut(RawEvent, Proxy<I>, DispatchData) + 'static,
+    {
+        let mut callback = callback;
+        let inner = unsafe {
+            self.inner.implement_raw::<I, _>(move |event, proxy, data| {
+                callback(event, Proxy::wrap(proxy), data)
+            })
+        };
+        Proxy::wrap(inner)
+    }
+
+    /// Assign this proxy to a `Filter` shared with other proxies of possibly other
+    /// interfaces, rather than giving it its own implementation
+    ///
+    /// The filter's event type `E` must be buildable from this interface's
+    /// `(Proxy<I>, I::Event)` pair, so that the callback can tell which object an event
+    /// came from.
+    pub fn assign<E>(self, filter: Filter<E>) -> Proxy<I>
+    where
+        E: From<(Proxy<I>, I::Event)> + 'static,
+        I::Event: wayland_commons::MessageGroup<Map = rust_imp::ProxyMap>,
+    {
+        let inner = unsafe { self.inner.assign::<I, E>(filter) };
+        Proxy::wrap(inner)
+    }
+}
+
+/// A shared event sink that several proxies can be assigned to at once
+///
+/// Cloning a `Filter` yields another handle to the very same callback, so many objects
+/// (for example all the `wl_output`s a client has bound) can funnel their events into
+/// one stateful handler without stashing a shared `Rc`/`Arc` in every implementation.
+pub struct Filter<E> {
+    pub(crate) callback: Rc<RefCell<dyn FnMut(E, DispatchData)>>,
+}
+
+impl<E: 'static> Filter<E> {
+    /// Create a new `Filter` around a callback
+    pub fn new<F: FnMut(E, DispatchData) + 'static>(callback: F) -> Filter<E> {
+        Filter {
+            callback: Rc::new(RefCell::new(callback)),
+        }
+    }
+}
+
+impl<E> Clone for Filter<E> {
+    fn clone(&self) -> Filter<E> {
+        Filter {
+            callback: self.callback.clone(),
+        }
+    }
+}
+
+/// Application state shared across a batch of dispatched events
+///
+/// This is a type alias for `&mut dyn Any`: handlers that were given one at
+/// dispatch time can `downcast_mut`/`downcast_ref` it back to whatever concrete state
+/// type they agreed on with the code that created the event queue.
+pub type DispatchData<'a> = &'a mut dyn Any;
+
+/// A handler for the events received by a wire object
+///
+/// Implementing just `receive` (as all existing implementations already do) is enough;
+/// `receive_with_data` has a default that forwards to it, ignoring the shared dispatch
+/// state, so nothing that predates `DispatchData` needs to change.
+pub trait Implementation<T, E> {
+    /// Process one event
+    fn receive(&mut self, event: E, object: T);
+
+    /// Process one event, with access to the state threaded through this dispatch
+    ///
+    /// The default implementation simply discards `data` and forwards to `receive`.
+    fn receive_with_data(&mut self, event: E, object: T, data: DispatchData) {
+        let _ = data;
+        self.receive(event, object)
+    }
+}
+
+impl<T, E, F: FnMut(E, T)> Implementation<T, E> for F {
+    fn receive(&mut self, event: E, object: T) {
+        (self)(event, object)
+    }
+}
+
+/// A queue of events waiting to be dispatched for the proxies assigned to it
+pub struct EventQueue {
+    inner: EventQueueInner,
+}
+
+impl EventQueue {
+    pub(crate) fn new(connection: Arc<Mutex<Connection>>) -> EventQueue {
+        EventQueue {
+            inner: EventQueueInner::new(connection),
+        }
+    }
+
+    /// Dispatch all the events already buffered for this queue, without blocking
+    pub fn dispatch_pending(&mut self, data: DispatchData) -> io::Result<u32> {
+        self.inner.dispatch_pending(data)
+    }
+
+    /// Dispatch the events already buffered for this queue; if there were none, block the
+    /// current thread on the socket until a read completes, then dispatch everything pending
+    pub fn dispatch(&mut self, data: DispatchData) -> io::Result<u32> {
+        self.inner.dispatch(data)
+    }
+
+    /// The file descriptor backing this queue's connection
+    ///
+    /// Register it with an external reactor (`mio`, `tokio`, ...) to be notified of
+    /// readiness instead of calling the blocking `dispatch`.
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+
+    /// Drive one round of dispatch against an external reactor
+    ///
+    /// Drains whatever is already buffered; if nothing was pending, awaits the queue's
+    /// fd becoming readable through `reactor` and reads once before dispatching again.
+    /// This lets the queue live inside an async runtime instead of owning a dedicated
+    /// blocking thread.
+    pub async fn dispatch_async<R: FdReady>(
+        &mut self,
+        reactor: &R,
+        data: DispatchData<'_>,
+    ) -> io::Result<u32> {
+        self.inner.dispatch_async(reactor, data).await
+    }
+}
+
+pub use rust_imp::FdReady;
+
+#[cfg(test)]
+mod tests {
+    use super::{DispatchData, Filter, Implementation};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Counter(u32);
+
+    impl Implementation<(), u32> for Counter {
+        fn receive(&mut self, event: u32, _object: ()) {
+            self.0 += event;
+        }
+    }
+
+    #[test]
+    fn receive_with_data_defaults_to_receive() {
+        // an Implementation that only defines `receive` must still be usable through
+        // `receive_with_data`, ignoring whatever DispatchData it is handed
+        let mut counter = Counter(0);
+        let mut state = 0u32;
+        let data: DispatchData = &mut state;
+        counter.receive_with_data(5, (), data);
+        assert_eq!(counter.0, 5);
+    }
+
+    #[test]
+    fn filter_clones_observe_the_same_callback() {
+        // cloning a Filter must hand out another handle to the very same callback, not a
+        // copy of it, so that several proxies assigned to different clones all fan into
+        // the same handler state
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let filter = {
+            let seen = seen.clone();
+            Filter::new(move |event: u32, _data: DispatchData| seen.borrow_mut().push(event))
+        };
+        let clone = filter.clone();
+
+        let mut state = 0u32;
+        (&mut *filter.callback.borrow_mut())(1, &mut state);
+        (&mut *clone.callback.borrow_mut())(2, &mut state);
+
+        assert_eq!(*seen.borrow(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+use wayland_commons::map::ObjectMap;
+use wayland_commons::wire::Message;
+
+use super::proxy::ObjectMeta;
+
+/// The socket-facing half of a client connection
+///
+/// Owns the object map (shared with every `ProxyInner` created on this connection) and
+/// the raw socket used to read and write wire messages.
+pub(crate) struct Connection {
+    pub(crate) map: Arc<Mutex<ObjectMap<ObjectMeta>>>,
+    socket: UnixStream,
+}
+
+impl Connection {
+    /// Wrap an already-connected socket, switching it to non-blocking mode
+    ///
+    /// The socket must be non-blocking so that `EventQueueInner::prepare_read`'s single-
+    /// reader barrier can't wedge a caller that loses the race but still ends up calling
+    /// `read_events` on a socket nothing has signalled as readable yet.
+    pub(crate) fn new(map: Arc<Mutex<ObjectMap<ObjectMeta>>>, socket: UnixStream) -> io::Result<Connection> {
+        socket.set_nonblocking(true)?;
+        Ok(Connection { map, socket })
+    }
+
+    pub(crate) fn write_message(&mut self, msg: &Message) -> io::Result<()> {
+        msg.write_to(&mut self.socket)
+    }
+
+    /// Read one message from the socket, decode it and push it onto its object's queue
+    /// buffer
+    ///
+    /// Only a single message is read per call; a message whose sender id is not in the
+    /// object map is dropped.
+    ///
+    /// The socket is non-blocking, so a spurious readiness notification (or a read that
+    /// raced another reader and lost) simply yields no new messages instead of erroring.
+    pub(crate) fn read_events(&mut self) -> io::Result<()> {
+        let msg = match Message::read_from(&mut self.socket) {
+            Ok(msg) => msg,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let map = self.map.lock().unwrap();
+        if let Some(object) = map.find(msg.sender_id) {
+            object.meta.buffer.lock().unwrap().push_back(msg);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
@@ -0,0 +1,294 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::connection::Connection;
+use super::queues::{create_queue_buffer, QueueBuffer};
+use DispatchData;
+
+/// Block the calling thread until `fd` is readable
+fn wait_readable(fd: RawFd) -> io::Result<()> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `pfd` is a single, stack-local `pollfd` of the right size, passed with a
+    // matching length of 1.
+    let ret = unsafe { libc::poll(&mut pfd, 1, -1) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// The client-side half of an event queue
+///
+/// Holds the buffer events for objects assigned to this queue accumulate into, plus a
+/// handle to the connection they are read from.
+pub(crate) struct EventQueueInner {
+    pub(crate) buffer: QueueBuffer,
+    connection: Arc<Mutex<Connection>>,
+    // Only one thread may actually read the socket at a time; the others must wait on
+    // (or cancel) their own `prepare_read`. See `prepare_read` / `ReadEventsGuard`.
+    reading: Arc<AtomicBool>,
+}
+
+impl EventQueueInner {
+    pub(crate) fn new(connection: Arc<Mutex<Connection>>) -> EventQueueInner {
+        EventQueueInner {
+            buffer: create_queue_buffer(),
+            connection,
+            reading: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Dispatch all the events already buffered for this queue, without blocking on the socket
+    ///
+    /// `data` is handed down to every `Implementation::receive_with_data` invoked while
+    /// draining the buffer, so that a compositor or client can process the whole batch
+    /// against the same borrowed state rather than stashing it in every closure.
+    pub(crate) fn dispatch_pending(&mut self, data: DispatchData) -> io::Result<u32> {
+        self.dispatch_buffer(data)
+    }
+
+    /// Dispatch the events already buffered; if there were none, block on the socket
+    /// until a read completes, then dispatch everything pending
+    ///
+    /// Goes through the same `prepare_read`/`reading` handshake as `dispatch_async`, so
+    /// a blocking caller on one thread and a reactor-driven caller on another never read
+    /// the (now non-blocking) socket at the same time: whichever loses the race to
+    /// `prepare_read` just waits for the winner to finish instead of racing it, and then
+    /// dispatches whatever that read brought into this queue's buffer.
+    pub(crate) fn dispatch(&mut self, mut data: DispatchData) -> io::Result<u32> {
+        let pending = self.dispatch_buffer(&mut *data)?;
+        if pending > 0 {
+            return Ok(pending);
+        }
+        match self.prepare_read() {
+            Some(guard) => {
+                wait_readable(self.as_raw_fd())?;
+                guard.read_events()?;
+            }
+            None => {
+                // another thread owns the read barrier right now; wait for it to
+                // release it rather than racing it on the non-blocking socket
+                while self.reading.load(Ordering::Acquire) {
+                    ::std::thread::yield_now();
+                }
+            }
+        }
+        self.dispatch_buffer(&mut *data)
+    }
+
+    /// The file descriptor backing this queue's connection
+    ///
+    /// Meant to be registered with an external reactor so the queue can be woken up on
+    /// readiness instead of owning a blocking `dispatch` loop.
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.connection.lock().unwrap().as_raw_fd()
+    }
+
+    /// Begin the non-blocking read handshake (mirrors libwayland's
+    /// `wl_display_prepare_read`)
+    ///
+    /// Returns `None` if another thread is already reading from this connection; in
+    /// that case the caller should just wait and dispatch whatever lands in its own
+    /// buffer as a side effect of that other read, rather than reading concurrently.
+    /// Otherwise, once this queue's `as_raw_fd` is known to be readable, consume the
+    /// returned guard with `ReadEventsGuard::read_events`; dropping it without calling
+    /// that cancels the read and releases the barrier for others.
+    pub(crate) fn prepare_read(&self) -> Option<ReadEventsGuard> {
+        if self.reading.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some(ReadEventsGuard {
+            connection: self.connection.clone(),
+            reading: self.reading.clone(),
+            read: false,
+        })
+    }
+
+    fn dispatch_buffer(&mut self, mut data: DispatchData) -> io::Result<u32> {
+        let mut dispatched = 0;
+        loop {
+            let next = self.buffer.lock().unwrap().pop_front();
+            let msg = match next {
+                Some(msg) => msg,
+                None => break,
+            };
+            let map = self.connection.lock().unwrap().map.clone();
+            let sender_id = msg.sender_id;
+            if let Some(object) = map.lock().unwrap().find(sender_id) {
+                let dispatcher = object.meta.dispatcher.clone();
+                let proxy = match super::ProxyInner::from_id(sender_id, map, self.connection.clone()) {
+                    Some(proxy) => proxy,
+                    None => continue,
+                };
+                let _ = dispatcher.lock().unwrap().dispatch(msg, proxy, &mut *data);
+            }
+            dispatched += 1;
+        }
+        Ok(dispatched)
+    }
+}
+
+/// An in-progress read handshake obtained from `EventQueueInner::prepare_read`
+///
+/// Must be driven to completion with `read_events`, once the fd is known to be
+/// readable, or dropped to cancel the read and let another thread take over.
+pub(crate) struct ReadEventsGuard {
+    connection: Arc<Mutex<Connection>>,
+    reading: Arc<AtomicBool>,
+    read: bool,
+}
+
+impl ReadEventsGuard {
+    /// Actually read from the socket, completing the handshake started by `prepare_read`
+    pub(crate) fn read_events(mut self) -> io::Result<()> {
+        self.read = true;
+        self.connection.lock().unwrap().read_events()
+    }
+}
+
+impl Drop for ReadEventsGuard {
+    fn drop(&mut self) {
+        // whether we actually read or were cancelled, the barrier must be released so
+        // another thread (or a future retry) can take its turn
+        let _ = self.read;
+        self.reading.store(false, Ordering::Release);
+    }
+}
+
+/// A future that yields control back to the executor exactly once before resolving
+///
+/// Used while waiting for another thread to finish its own `prepare_read`/`read_events`
+/// handshake: there is nothing to register with an external reactor in that case (we
+/// don't own the fd wait), but just returning `Poll::Ready` immediately would make a
+/// `while dispatch_async(..).await? == 0 {}` loop spin with no await point at all.
+struct YieldNow(bool);
+
+fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+impl ::std::future::Future for YieldNow {
+    type Output = ();
+
+    fn poll(
+        mut self: ::std::pin::Pin<&mut Self>,
+        cx: &mut ::std::task::Context,
+    ) -> ::std::task::Poll<()> {
+        if self.0 {
+            ::std::task::Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            ::std::task::Poll::Pending
+        }
+    }
+}
+
+/// A reactor-agnostic seam for integrating an `EventQueueInner` with an async runtime
+///
+/// Implement this for whatever reactor you're driving the queue from (tokio, smol, a
+/// hand-rolled `mio::Poll` loop, ...); it only needs to resolve once the given fd
+/// reports readable.
+pub trait FdReady {
+    type Future: ::std::future::Future<Output = io::Result<()>>;
+
+    fn readable(&self, fd: RawFd) -> Self::Future;
+}
+
+impl EventQueueInner {
+    /// Drive one round of dispatch against an external reactor instead of blocking the
+    /// calling thread on the socket
+    ///
+    /// This is the non-blocking counterpart to `dispatch`: it drains whatever is
+    /// already buffered, and only falls back to `prepare_read` + awaiting readiness +
+    /// `read_events` if nothing was pending, so a queue can live inside a tokio or smol
+    /// runtime rather than owning a dedicated blocking thread.
+    pub(crate) async fn dispatch_async<R: FdReady>(
+        &mut self,
+        reactor: &R,
+        mut data: DispatchData<'_>,
+    ) -> io::Result<u32> {
+        let pending = self.dispatch_pending(&mut *data)?;
+        if pending > 0 {
+            return Ok(pending);
+        }
+        match self.prepare_read() {
+            Some(guard) => {
+                reactor.readable(self.as_raw_fd()).await?;
+                guard.read_events()?;
+            }
+            None => {
+                // another thread is already reading on our behalf; yield to the executor
+                // for as long as it holds the barrier, so a caller looping on this future
+                // can't busy-spin until it finishes
+                while self.reading.load(Ordering::Acquire) {
+                    yield_now().await;
+                }
+            }
+        }
+        self.dispatch_pending(&mut *data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wait_readable, yield_now};
+    use std::future::Future;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    // `EventQueueInner`/`ReadEventsGuard` can't be exercised directly in a unit test: both
+    // are built on an `ObjectMap<ObjectMeta>` and `Message` from `wayland_commons`, which
+    // is an external crate not vendored in this checkout. What follows covers the two
+    // pieces of this fix that are self-contained: the blocking-poll helper the new
+    // `dispatch` uses to wait for the non-blocking socket, and the manual yield future
+    // that gives `dispatch_async`'s barrier-contended path an actual await point.
+
+    #[test]
+    fn wait_readable_returns_once_data_is_available() {
+        let (mut a, b) = UnixStream::pair().unwrap();
+        a.write_all(b"x").unwrap();
+        // already readable, so this must return promptly rather than blocking forever
+        wait_readable(b.as_raw_fd()).unwrap();
+    }
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(::std::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        unsafe { Waker::from_raw(RawWaker::new(::std::ptr::null(), &VTABLE)) }
+    }
+
+    #[test]
+    fn yield_now_polls_pending_once_then_ready() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(yield_now());
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn reading_barrier_is_single_owner() {
+        // mirrors the swap/release dance `prepare_read`/`ReadEventsGuard::drop` do around
+        // the shared `reading` flag, without needing a full `Connection` to back it
+        let reading = AtomicBool::new(false);
+        assert!(!reading.swap(true, Ordering::AcqRel));
+        // a second concurrent "prepare_read" must observe the barrier as taken
+        assert!(reading.swap(true, Ordering::AcqRel));
+        reading.store(false, Ordering::Release);
+        assert!(!reading.load(Ordering::Acquire));
+    }
+}
@@ -0,0 +1,147 @@
+mod connection;
+mod event_queue;
+mod proxy;
+mod queues;
+mod raw_event;
+
+pub(crate) use self::connection::Connection;
+pub(crate) use self::event_queue::EventQueueInner;
+pub use self::event_queue::FdReady;
+pub(crate) use self::proxy::{NewProxyInner, ObjectMeta, ProxyInner};
+pub use self::raw_event::RawEvent;
+
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use downcast_rs::{impl_downcast, Downcast};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wayland_commons::map::ObjectMap;
+use wayland_commons::wire::Message;
+use wayland_commons::MessageGroup;
+
+use {DispatchData, Filter, Implementation, Interface, Proxy};
+
+/// The concrete `ObjectMap` used by client-side proxies
+pub(crate) type ProxyMap = ObjectMap<ObjectMeta>;
+
+/// Object-safe handler stored behind each object's `ObjectMeta::dispatcher`
+///
+/// `Downcast` lets `ProxyInner::is_implemented_with` check which concrete
+/// `ImplDispatcher` (if any) is currently installed without knowing its type ahead of
+/// time. Dispatch runs on a single thread (the one driving the event queue), so, unlike
+/// the rest of `ObjectMeta`, it is not required to be `Send`.
+pub(crate) trait Dispatch: Downcast {
+    fn dispatch(&mut self, msg: Message, proxy: ProxyInner, data: DispatchData) -> Result<(), ()>;
+}
+
+impl_downcast!(Dispatch);
+
+pub(crate) type Dispatcher = Box<dyn Dispatch>;
+
+/// The dispatcher installed on an object that has not been implemented (yet)
+///
+/// Events routed to it are simply discarded; this is only ever a transient state, as
+/// the dispatcher is invariably replaced once a `NewProxyInner` is `implement`-ed.
+struct NoOpDispatcher;
+
+impl Dispatch for NoOpDispatcher {
+    fn dispatch(&mut self, _msg: Message, _proxy: ProxyInner, _data: DispatchData) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn default_dispatcher() -> Arc<Mutex<Dispatcher>> {
+    Arc::new(Mutex::new(Box::new(NoOpDispatcher)))
+}
+
+/// Bridges a typed `Implementation<Proxy<I>, I::Event>` into the object-safe `Dispatch`
+pub(crate) struct ImplDispatcher<I: Interface, Impl: Implementation<Proxy<I>, I::Event>> {
+    implementation: Impl,
+    _i: PhantomData<*const I>,
+}
+
+impl<I, Impl> Dispatch for ImplDispatcher<I, Impl>
+where
+    I: Interface + 'static,
+    Impl: Implementation<Proxy<I>, I::Event> + 'static,
+    I::Event: MessageGroup<Map = ProxyMap> + 'static,
+{
+    fn dispatch(&mut self, msg: Message, proxy: ProxyInner, data: DispatchData) -> Result<(), ()> {
+        let event = I::Event::from_raw(msg, &proxy.map)?;
+        self.implementation
+            .receive_with_data(event, Proxy::<I>::wrap(proxy), data);
+        Ok(())
+    }
+}
+
+pub(crate) fn make_dispatcher<I, Impl>(implementation: Impl) -> Arc<Mutex<Dispatcher>>
+where
+    I: Interface + 'static,
+    Impl: Implementation<Proxy<I>, I::Event> + 'static,
+    I::Event: MessageGroup<Map = ProxyMap> + 'static,
+{
+    Arc::new(Mutex::new(Box::new(ImplDispatcher {
+        implementation,
+        _i: PhantomData,
+    })))
+}
+
+/// Dispatcher for objects implemented with a raw callback instead of a typed
+/// `Implementation`: the wire message is handed over undecoded, as a `RawEvent`
+struct RawDispatcher<F: FnMut(RawEvent, ProxyInner, DispatchData)> {
+    callback: F,
+}
+
+impl<F: FnMut(RawEvent, ProxyInner, DispatchData) + 'static> Dispatch for RawDispatcher<F> {
+    fn dispatch(&mut self, msg: Message, proxy: ProxyInner, data: DispatchData) -> Result<(), ()> {
+        let event = RawEvent {
+            opcode: msg.opcode,
+            object_id: proxy.id(),
+            args: msg.args,
+        };
+        (self.callback)(event, proxy, data);
+        Ok(())
+    }
+}
+
+pub(crate) fn make_raw_dispatcher<F>(callback: F) -> Arc<Mutex<Dispatcher>>
+where
+    F: FnMut(RawEvent, ProxyInner, DispatchData) + 'static,
+{
+    Arc::new(Mutex::new(Box::new(RawDispatcher { callback })))
+}
+
+/// Dispatcher for an object assigned to a shared `Filter<E>` rather than its own
+/// `Implementation`
+struct FilterDispatcher<I: Interface, E> {
+    filter: Filter<E>,
+    _i: PhantomData<*const I>,
+}
+
+impl<I, E> Dispatch for FilterDispatcher<I, E>
+where
+    I: Interface + 'static,
+    E: From<(Proxy<I>, I::Event)> + 'static,
+    I::Event: MessageGroup<Map = ProxyMap> + 'static,
+{
+    fn dispatch(&mut self, msg: Message, proxy: ProxyInner, data: DispatchData) -> Result<(), ()> {
+        let event = I::Event::from_raw(msg, &proxy.map)?;
+        let wrapped: E = (Proxy::<I>::wrap(proxy), event).into();
+        (&mut *self.filter.callback.borrow_mut())(wrapped, data);
+        Ok(())
+    }
+}
+
+pub(crate) fn make_filter_dispatcher<I, E>(filter: Filter<E>) -> Arc<Mutex<Dispatcher>>
+where
+    I: Interface + 'static,
+    E: From<(Proxy<I>, I::Event)> + 'static,
+    I::Event: MessageGroup<Map = ProxyMap> + 'static,
+{
+    Arc::new(Mutex::new(Box::new(FilterDispatcher {
+        filter,
+        _i: PhantomData,
+    })))
+}
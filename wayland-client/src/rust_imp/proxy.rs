@@ -1,4 +1,5 @@
-use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use wayland_commons::map::{Object, ObjectMap, ObjectMetadata};
@@ -7,13 +8,100 @@ use wayland_commons::MessageGroup;
 use super::connection::Connection;
 use super::queues::QueueBuffer;
 use super::{Dispatcher, EventQueueInner};
-use {Implementation, Interface, Proxy};
+use {DispatchData, Filter, Implementation, Interface, Proxy};
+
+/// A typed container for the user data associated with a proxy
+///
+/// This wraps a `Box<dyn Any + Send + Sync>` behind a shared, lockable slot, so that
+/// cloning a `UserData` (as happens whenever the `ObjectMeta`/`ProxyInner` it lives in
+/// is cloned) gives access to the very same stored value rather than a copy of a raw
+/// pointer. The value is dropped (and thus freed) by `clear`, which `ProxyInner::send`
+/// calls when a destructor request is sent for the object, or otherwise when the last
+/// clone of the `UserData` goes away.
+#[derive(Clone)]
+pub(crate) struct UserData {
+    inner: Arc<Mutex<Option<Box<dyn Any + Send + Sync>>>>,
+}
+
+impl UserData {
+    /// Create a new, empty `UserData`
+    pub(crate) fn new() -> UserData {
+        UserData {
+            inner: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Set the user data, overwriting (and dropping) whatever was previously stored
+    pub(crate) fn set<T: Send + Sync + 'static>(&self, value: T) {
+        *self.inner.lock().unwrap() = Some(Box::new(value));
+    }
+
+    /// Run `f` against the stored user data, if any was set and it is of type `T`
+    ///
+    /// Returns `None` if no value was ever set, or if the stored value is not of type
+    /// `T`. The lock guarding the value is held for the duration of `f`, so, unlike a
+    /// borrowed-reference API, there is no way to retain a reference past a later
+    /// `set`/`clear` call that would otherwise leave it dangling.
+    pub(crate) fn with<T: 'static, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        let guard = self.inner.lock().unwrap();
+        guard.as_ref().and_then(|val| val.downcast_ref::<T>()).map(f)
+    }
+
+    /// Drop whatever value is currently stored, leaving this `UserData` empty
+    pub(crate) fn clear(&self) {
+        *self.inner.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserData;
+
+    #[test]
+    fn get_set_roundtrip() {
+        let ud = UserData::new();
+        assert_eq!(ud.with(|v: &String| v.clone()), None);
+        ud.set("hello".to_string());
+        assert_eq!(ud.with(|v: &String| v.clone()), Some("hello".to_string()));
+        // wrong type is treated like nothing was ever set
+        assert_eq!(ud.with(|v: &u32| *v), None);
+    }
+
+    #[test]
+    fn clone_sees_updated_data() {
+        let ud = UserData::new();
+        let clone = ud.clone();
+        ud.set(1u32);
+        assert_eq!(clone.with(|v: &u32| *v), Some(1));
+        ud.set(2u32);
+        assert_eq!(clone.with(|v: &u32| *v), Some(2));
+    }
+
+    #[test]
+    fn clear_empties_the_slot() {
+        let ud = UserData::new();
+        ud.set(42u32);
+        ud.clear();
+        assert_eq!(ud.with(|v: &u32| *v), None);
+    }
+
+    #[test]
+    fn set_after_with_does_not_invalidate_the_result() {
+        // `with` only ever hands `f` a borrow scoped to the lock; the value it returns
+        // must remain valid even after a later `set` drops the old stored value.
+        let ud = UserData::new();
+        ud.set("first".to_string());
+        let copy = ud.with(|v: &String| v.clone()).unwrap();
+        ud.set("second".to_string());
+        assert_eq!(copy, "first");
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct ObjectMeta {
     pub(crate) buffer: QueueBuffer,
     pub(crate) alive: Arc<AtomicBool>,
-    pub(crate) user_data: Arc<AtomicPtr<()>>,
+    pub(crate) user_data: UserData,
     pub(crate) dispatcher: Arc<Mutex<Dispatcher>>,
     pub(crate) server_destroyed: bool,
     pub(crate) client_destroyed: bool,
@@ -24,7 +112,7 @@ impl ObjectMetadata for ObjectMeta {
         ObjectMeta {
             buffer: self.buffer.clone(),
             alive: Arc::new(AtomicBool::new(true)),
-            user_data: Arc::new(AtomicPtr::new(::std::ptr::null_mut())),
+            user_data: UserData::new(),
             dispatcher: super::default_dispatcher(),
             server_destroyed: false,
             client_destroyed: false,
@@ -37,7 +125,7 @@ impl ObjectMeta {
         ObjectMeta {
             buffer,
             alive: Arc::new(AtomicBool::new(true)),
-            user_data: Arc::new(AtomicPtr::new(::std::ptr::null_mut())),
+            user_data: UserData::new(),
             dispatcher: super::default_dispatcher(),
             server_destroyed: false,
             client_destroyed: false,
@@ -48,7 +136,7 @@ impl ObjectMeta {
         ObjectMeta {
             buffer: super::queues::create_queue_buffer(),
             alive: Arc::new(AtomicBool::new(false)),
-            user_data: Arc::new(AtomicPtr::new(::std::ptr::null_mut())),
+            user_data: UserData::new(),
             dispatcher: super::default_dispatcher(),
             server_destroyed: true,
             client_destroyed: true,
@@ -99,12 +187,16 @@ impl ProxyInner {
         }
     }
 
-    pub fn set_user_data(&self, ptr: *mut ()) {
-        self.object.meta.user_data.store(ptr, Ordering::Release)
+    pub fn set_user_data<T: Send + Sync + 'static>(&self, value: T) {
+        self.object.meta.user_data.set(value)
     }
 
-    pub fn get_user_data(&self) -> *mut () {
-        self.object.meta.user_data.load(Ordering::Acquire)
+    /// Run `f` against the stored user data, if any was set and it is of type `T`
+    ///
+    /// See `UserData::with` for why this takes a closure rather than handing back a
+    /// `&T` directly.
+    pub fn with_user_data<T: 'static, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.object.meta.user_data.with(f)
     }
 
     pub(crate) fn send<I: Interface>(&self, msg: I::Request) {
@@ -131,6 +223,7 @@ impl ProxyInner {
         let _ = conn_lock.write_message(&msg).expect("Sending a message failed.");
         if destructor {
             self.object.meta.alive.store(false, Ordering::Release);
+            self.object.meta.user_data.clear();
             {
                 // cleanup the map as appropriate
                 let mut map = conn_lock.map.lock().unwrap();
@@ -240,4 +333,59 @@ impl NewProxyInner {
             object,
         }
     }
+
+    /// Like `implement`, but events are delivered undecoded as a `RawEvent` instead of
+    /// going through a typed `Implementation`
+    ///
+    /// This is meant for code that needs to observe or forward events for interfaces it
+    /// doesn't statically know about, such as protocol proxies, loggers and debuggers.
+    /// `I` is only used to describe the object if it must be replaced by a dummy
+    /// already-dead one; it plays no part in how events are delivered.
+    pub(crate) unsafe fn implement_raw<I: Interface, F>(self, callback: F) -> ProxyInner
+    where
+        F: FnMut(super::RawEvent, ProxyInner, DispatchData) + 'static,
+    {
+        let object = self.map.lock().unwrap().with(self.id, |obj| {
+            obj.meta.dispatcher = super::make_raw_dispatcher(callback);
+            obj.clone()
+        });
+
+        let object = match object {
+            Ok(obj) => obj,
+            Err(()) => Object::from_interface::<I>(1, ObjectMeta::dead()),
+        };
+
+        ProxyInner {
+            map: self.map,
+            connection: self.connection,
+            id: self.id,
+            object,
+        }
+    }
+
+    /// Like `implement`, but delegates dispatching to a shared `Filter` instead of
+    /// installing a private implementation, so several proxies (of possibly different
+    /// interfaces) can funnel their events into the same callback
+    pub(crate) unsafe fn assign<I: Interface, E>(self, filter: Filter<E>) -> ProxyInner
+    where
+        E: From<(Proxy<I>, I::Event)> + 'static,
+        I::Event: MessageGroup<Map = super::ProxyMap>,
+    {
+        let object = self.map.lock().unwrap().with(self.id, |obj| {
+            obj.meta.dispatcher = super::make_filter_dispatcher::<I, E>(filter);
+            obj.clone()
+        });
+
+        let object = match object {
+            Ok(obj) => obj,
+            Err(()) => Object::from_interface::<I>(1, ObjectMeta::dead()),
+        };
+
+        ProxyInner {
+            map: self.map,
+            connection: self.connection,
+            id: self.id,
+            object,
+        }
+    }
 }
@@ -0,0 +1,17 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use wayland_commons::wire::Message;
+
+/// A shared buffer of undispatched messages for a single event queue
+///
+/// Incoming messages for an object are pushed here by the reading thread, and
+/// drained by whichever `EventQueueInner` owns the buffer at dispatch time. Several
+/// `ObjectMeta` can share the same buffer (they belong to the same event queue), which
+/// is why it is reference-counted rather than owned outright.
+pub(crate) type QueueBuffer = Arc<Mutex<VecDeque<Message>>>;
+
+/// Create a new, empty message buffer for an event queue
+pub(crate) fn create_queue_buffer() -> QueueBuffer {
+    Arc::new(Mutex::new(VecDeque::new()))
+}
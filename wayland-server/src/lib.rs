@@ -0,0 +1,43 @@
+extern crate libc;
+
+mod rust_imp;
+
+use std::io;
+use std::rc::Rc;
+
+pub use rust_imp::Credentials;
+
+/// A handle to a connected client
+///
+/// Cloning a `Client` gives another handle to the same connection.
+#[derive(Clone)]
+pub struct Client {
+    connection: Rc<rust_imp::Connection>,
+}
+
+impl Client {
+    /// The pid/uid/gid of the process on the other end of this client's connection
+    ///
+    /// The credentials are read once (via `SO_PEERCRED` on Linux, the
+    /// `LOCAL_PEERCRED`/`getpeereid` equivalent on the BSDs and macOS) and cached from
+    /// then on, since they cannot change over the lifetime of the socket. Returns an
+    /// error rather than panicking on platforms where the lookup isn't available.
+    pub fn credentials(&self) -> io::Result<Credentials> {
+        self.connection.credentials()
+    }
+}
+
+/// A handle to a protocol object living on a client's connection
+pub struct Resource<I> {
+    client: Client,
+    _i: ::std::marker::PhantomData<*const I>,
+}
+
+impl<I> Resource<I> {
+    /// The credentials of the client that owns this resource
+    ///
+    /// See `Client::credentials` for details.
+    pub fn credentials(&self) -> io::Result<Credentials> {
+        self.client.credentials()
+    }
+}
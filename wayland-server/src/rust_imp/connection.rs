@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use super::credentials::{get_peer_credentials, Credentials};
+
+/// The socket-facing half of a client's connection to the display
+pub(crate) struct Connection {
+    socket: UnixStream,
+    // `Credentials` are fixed for the lifetime of the socket, so we only ever look
+    // them up once.
+    credentials: RefCell<Option<Result<Credentials, io::ErrorKind>>>,
+}
+
+impl Connection {
+    pub(crate) fn new(socket: UnixStream) -> Connection {
+        Connection {
+            socket,
+            credentials: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn credentials(&self) -> io::Result<Credentials> {
+        if let Some(cached) = *self.credentials.borrow() {
+            return cached.map_err(io::Error::from);
+        }
+        let result = get_peer_credentials(self.socket.as_raw_fd());
+        *self.credentials.borrow_mut() = Some(match &result {
+            Ok(creds) => Ok(*creds),
+            Err(err) => Err(err.kind()),
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Connection;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn credentials_are_looked_up_and_cached() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let conn = Connection::new(a);
+
+        let creds = conn.credentials().expect("local socketpair credentials should resolve");
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(creds.pid, unsafe { libc::getpid() });
+        assert_eq!(creds.uid, unsafe { libc::getuid() });
+        assert_eq!(creds.gid, unsafe { libc::getgid() });
+
+        // second call must hit the cache and return the very same value
+        assert_eq!(*conn.credentials.borrow(), Some(Ok(creds)));
+        assert_eq!(conn.credentials().unwrap(), creds);
+    }
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// The pid, uid and gid of the client on the other end of a connection
+///
+/// These are fixed for the whole lifetime of the underlying Unix socket, so callers
+/// should not expect them to change (or to reflect `setuid`/`setgid` calls the peer
+/// process makes after connecting).
+///
+/// On macOS and the BSDs `pid` is always 0, as `getpeereid` does not report it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: libc::pid_t,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn get_peer_credentials(fd: RawFd) -> io::Result<Credentials> {
+    use std::mem;
+
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut _,
+            &mut len,
+        )
+    };
+
+    if ret == 0 {
+        Ok(Credentials {
+            pid: cred.pid,
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub(crate) fn get_peer_credentials(fd: RawFd) -> io::Result<Credentials> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+
+    let ret = unsafe { libc::getpeereid(fd, &mut uid, &mut gid) };
+
+    if ret == 0 {
+        // BSD's getpeereid() has no equivalent for the pid
+        Ok(Credentials { pid: 0, uid, gid })
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub(crate) fn get_peer_credentials(_fd: RawFd) -> io::Result<Credentials> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "peer credentials are not supported on this platform",
+    ))
+}
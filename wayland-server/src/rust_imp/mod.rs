@@ -0,0 +1,5 @@
+pub(crate) mod connection;
+pub(crate) mod credentials;
+
+pub(crate) use self::connection::Connection;
+pub use self::credentials::Credentials;